@@ -1,28 +1,166 @@
 /*
- * Copyright (c) 2020.  Shoyo Inokuchi.
+ * Copyright (c) 2020 - 2021.  Shoyo Inokuchi.
  * Please refer to github.com/shoyo/jin for more information about this project and its license.
  */
 
 use super::policy::Policy;
 use crate::common::constants::BufferFrameIdT;
+use std::collections::{HashMap, VecDeque};
 
-/// An LRU eviction policy for the database buffer.
-pub struct LRUPolicy {}
+/// Number of generations tracked by the multi-generational LRU. Frames age from the youngest
+/// generation (index `GENERATIONS - 1`) towards the oldest (index `0`); `evict()` takes its
+/// victim from the oldest generation that is non-empty.
+const GENERATIONS: usize = 4;
+
+/// Once a generation grows past the oldest generation's size by more than this many frames, its
+/// tail is demoted one generation older to keep the generations balanced.
+const AGING_THRESHOLD: usize = 8;
+
+/// A Multi-Generational LRU eviction policy for the database buffer.
+///
+/// Unlike a single globally-ordered LRU list, frames are bucketed into `GENERATIONS` age bands.
+/// `unpin` always places a frame in the youngest generation; a frame that is unpinned again while
+/// already evictable is promoted back to the youngest generation and its access counter is
+/// bumped, so repeatedly touched frames survive longer without requiring a full list reshuffle.
+/// `evict()` only ever walks the oldest non-empty generation, giving frames a "second chance"
+/// (per-frame access counter) before they are actually reclaimed, which resists thrashing under
+/// scan-heavy workloads that would flush a plain single-list LRU.
+pub struct LRUPolicy {
+    /// `generations[0]` is the oldest, `generations[GENERATIONS - 1]` is the youngest.
+    generations: Vec<VecDeque<BufferFrameIdT>>,
+
+    /// Per-frame access counter, bumped on every `unpin` of an already-evictable frame and
+    /// cleared by `evict()` as it gives a frame its second chance.
+    access_count: HashMap<BufferFrameIdT, u32>,
+
+    /// Frames currently pinned (in use), and therefore not present in any generation.
+    pinned: HashMap<BufferFrameIdT, bool>,
+}
+
+impl LRUPolicy {
+    /// Remove a frame from whichever generation currently holds it, if any.
+    fn remove_from_generations(&mut self, frame_id: BufferFrameIdT) {
+        for generation in self.generations.iter_mut() {
+            if let Some(pos) = generation.iter().position(|&id| id == frame_id) {
+                generation.remove(pos);
+                return;
+            }
+        }
+    }
+
+    /// When a younger generation grows too large relative to the oldest, demote its tail one
+    /// generation older. This is the actual MGLRU aging: frames that have been protected in a
+    /// hot generation for a long time get pushed back towards generation 0, where `evict()`
+    /// looks first, instead of being protected indefinitely.
+    fn age(&mut self) {
+        let oldest_len = self.generations[0].len();
+        for i in (1..GENERATIONS).rev() {
+            if self.generations[i].len() > oldest_len + AGING_THRESHOLD {
+                if let Some(frame_id) = self.generations[i].pop_front() {
+                    self.generations[i - 1].push_back(frame_id);
+                }
+            }
+        }
+    }
+}
 
 impl Policy for LRUPolicy {
     fn new() -> Self {
-        Self {}
+        let mut generations = Vec::with_capacity(GENERATIONS);
+        for _ in 0..GENERATIONS {
+            generations.push(VecDeque::new());
+        }
+        Self {
+            generations,
+            access_count: HashMap::new(),
+            pinned: HashMap::new(),
+        }
     }
 
+    /// Scan the oldest non-empty generation for the first frame whose access counter is zero,
+    /// clearing counters of frames it passes over (second-chance) and demoting any frame it skips
+    /// back onto the tail of its generation so it gets another look after the others.
     fn evict(&mut self) -> Result<BufferFrameIdT, String> {
-        todo!()
+        for i in 0..GENERATIONS {
+            let len = self.generations[i].len();
+            // The first lap clears every counter it passes, so a second lap over a non-empty
+            // generation always finds a victim in it.
+            for _ in 0..2 * len {
+                let frame_id = match self.generations[i].pop_front() {
+                    Some(id) => id,
+                    None => break,
+                };
+                let count = self.access_count.entry(frame_id).or_insert(0);
+                if *count == 0 {
+                    self.access_count.remove(&frame_id);
+                    return Ok(frame_id);
+                }
+                *count = 0;
+                self.generations[i].push_back(frame_id);
+            }
+        }
+        Err("no evictable frame: all frames are pinned".to_string())
     }
 
+    /// Remove a frame from the evictable set because it is now in use.
     fn pin(&mut self, frame_id: BufferFrameIdT) {
-        todo!()
+        self.remove_from_generations(frame_id);
+        self.access_count.remove(&frame_id);
+        self.pinned.insert(frame_id, true);
     }
 
+    /// Make a frame evictable again, placing it in the youngest generation. A frame that is
+    /// already evictable (unpinned again without an intervening pin) is promoted: it is moved to
+    /// the youngest generation and its access counter is bumped, giving it a better chance of
+    /// surviving the next eviction scan.
     fn unpin(&mut self, frame_id: BufferFrameIdT) {
-        todo!()
+        self.pinned.remove(&frame_id);
+
+        if self.access_count.contains_key(&frame_id) {
+            self.remove_from_generations(frame_id);
+            *self.access_count.entry(frame_id).or_insert(0) += 1;
+        } else {
+            self.access_count.insert(frame_id, 0);
+        }
+
+        self.generations[GENERATIONS - 1].push_back(frame_id);
+        self.age();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evict_picks_least_recently_unpinned_frame() {
+        let mut policy = LRUPolicy::new();
+        policy.unpin(1);
+        policy.unpin(2);
+        policy.unpin(3);
+
+        assert_eq!(policy.evict().unwrap(), 1);
+        assert_eq!(policy.evict().unwrap(), 2);
+        assert_eq!(policy.evict().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_pinned_frame_is_not_evictable() {
+        let mut policy = LRUPolicy::new();
+        policy.unpin(1);
+        policy.unpin(2);
+        policy.pin(1);
+
+        assert_eq!(policy.evict().unwrap(), 2);
+        assert!(policy.evict().is_err());
+    }
+
+    #[test]
+    fn test_evict_with_all_frames_pinned_returns_err() {
+        let mut policy = LRUPolicy::new();
+        policy.unpin(1);
+        policy.pin(1);
+
+        assert!(policy.evict().is_err());
     }
-}
\ No newline at end of file
+}
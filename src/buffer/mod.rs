@@ -5,7 +5,8 @@
 
 use crate::common::{BufferFrameIdT, PageIdT};
 use crate::page::Page;
-use std::collections::{HashMap, LinkedList};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
 
 pub mod eviction_policies;
@@ -14,21 +15,195 @@ pub mod manager;
 /// Type alias for a page protected by a R/W latch for concurrent access.
 pub type PageLatch = Arc<RwLock<Option<Box<dyn Page>>>>;
 
+/// Number of bits of a `BufferFrameIdT` given to each part of the packed `{shard, index,
+/// generation}` triple. `index` gets the most room since a shard's slot count is the part most
+/// likely to grow with pool size; `generation` only needs enough range to make accidental
+/// wrap-around collisions between a stale and a fresh handle vanishingly unlikely.
+const GENERATION_BITS: u32 = 8;
+const INDEX_BITS: u32 = 16;
+const SHARD_BITS: u32 = 8;
+
+const GENERATION_MASK: u32 = (1 << GENERATION_BITS) - 1;
+const INDEX_MASK: u32 = (1 << INDEX_BITS) - 1;
+
+fn pack_frame_id(shard: u32, index: u32, generation: u32) -> BufferFrameIdT {
+    debug_assert!(shard <= (1 << SHARD_BITS) - 1);
+    debug_assert!(index <= INDEX_MASK);
+    debug_assert!(generation <= GENERATION_MASK);
+    (shard << (INDEX_BITS + GENERATION_BITS)) | (index << GENERATION_BITS) | generation
+}
+
+fn unpack_frame_id(frame_id: BufferFrameIdT) -> (u32, u32, u32) {
+    let generation = frame_id & GENERATION_MASK;
+    let index = (frame_id >> GENERATION_BITS) & INDEX_MASK;
+    let shard = frame_id >> (INDEX_BITS + GENERATION_BITS);
+    (shard, index, generation)
+}
+
+/// A shard of the buffer's frame pool. Each shard owns a contiguous range of frame slots, a
+/// free-list stack of empty slots, and its own lock, so concurrent allocation/eviction against
+/// different shards never contends on the same mutex.
+struct Shard {
+    frames: Vec<PageLatch>,
+    state: Mutex<ShardState>,
+}
+
+struct ShardState {
+    /// Stack of free slot indices local to this shard; popping/pushing is O(1).
+    free: Vec<u32>,
+    /// Bumped every time a slot is returned to the free-list, so a stale `BufferFrameIdT` held by another
+    /// thread can be detected and rejected instead of silently aliasing whatever page now
+    /// occupies the slot.
+    generations: Vec<u32>,
+}
+
+impl Shard {
+    fn new(capacity: usize) -> Self {
+        let mut frames = Vec::with_capacity(capacity);
+        let mut free = Vec::with_capacity(capacity);
+        for i in (0..capacity).rev() {
+            frames.push(Arc::new(RwLock::new(None)));
+            free.push(i as u32);
+        }
+        Self {
+            frames,
+            state: Mutex::new(ShardState {
+                free,
+                generations: vec![0; capacity],
+            }),
+        }
+    }
+}
+
 /// The database buffer and associated data structures.
+///
+/// The frame pool is a sharded concurrent slab rather than one flat `Vec` behind a single lock:
+/// frames are partitioned across `num_shards` shards, each with its own free-list and lock, so
+/// allocation and eviction against different shards don't serialize on each other. A
+/// `BufferFrameIdT` is a compound handle packing `{shard, index, generation}`; the generation is
+/// bumped every time a slot is reused so a stale handle is rejected rather than silently
+/// aliasing whatever page now lives in that slot.
+///
 /// Functions should be wary of the order in which they lock the buffer's data structures to
 /// prevent deadlocks.
 pub struct Buffer {
-    pool: Vec<PageLatch>,
+    shards: Vec<Shard>,
     page_table: RwLock<HashMap<PageIdT, BufferFrameIdT>>,
+    next_shard: AtomicUsize,
 }
 
 impl Buffer {
+    /// Construct a buffer pool of `size` frames, split as evenly as possible across
+    /// `num_shards` shards.
     pub fn new(size: BufferFrameIdT) -> Self {
-        let mut pool = Vec::with_capacity(size as usize);
-        let page_table = RwLock::new(HashMap::new());
-        for _ in 0..size {
-            pool.push(Arc::new(RwLock::new(None)));
+        let num_shards = num_cpus();
+        Self::with_shards(size, num_shards)
+    }
+
+    fn with_shards(size: BufferFrameIdT, num_shards: usize) -> Self {
+        let size = size as usize;
+        let base = size / num_shards;
+        let remainder = size % num_shards;
+
+        let mut shards = Vec::with_capacity(num_shards);
+        for i in 0..num_shards {
+            let capacity = base + if i < remainder { 1 } else { 0 };
+            shards.push(Shard::new(capacity));
+        }
+
+        Self {
+            shards,
+            page_table: RwLock::new(HashMap::new()),
+            next_shard: AtomicUsize::new(0),
+        }
+    }
+
+    /// Allocate a free frame, round-robining across shards so concurrent allocators spread their
+    /// contention out instead of piling onto one shard's lock. Returns `None` once every shard's
+    /// free-list is empty.
+    pub fn allocate_frame(&self) -> Option<BufferFrameIdT> {
+        let num_shards = self.shards.len();
+        let start = self.next_shard.fetch_add(1, Ordering::Relaxed) % num_shards;
+
+        for offset in 0..num_shards {
+            let shard_idx = (start + offset) % num_shards;
+            let shard = &self.shards[shard_idx];
+            let mut state = shard.state.lock().unwrap();
+            if let Some(index) = state.free.pop() {
+                let generation = state.generations[index as usize];
+                return Some(pack_frame_id(shard_idx as u32, index, generation));
+            }
+        }
+        None
+    }
+
+    /// Return a frame to its shard's free-list, bumping its generation so any other handle still
+    /// referring to the old occupant of the slot is rejected by `validate_frame`.
+    pub fn deallocate_frame(&self, frame_id: BufferFrameIdT) {
+        let (shard_idx, index, generation) = unpack_frame_id(frame_id);
+        let shard = &self.shards[shard_idx as usize];
+        let mut state = shard.state.lock().unwrap();
+
+        assert_eq!(
+            state.generations[index as usize], generation,
+            "stale frame id {} does not match current generation",
+            frame_id
+        );
+
+        *shard.frames[index as usize].write().unwrap() = None;
+        state.generations[index as usize] = generation.wrapping_add(1) & GENERATION_MASK;
+        state.free.push(index);
+    }
+
+    /// Return the frame's page latch if `frame_id`'s generation still matches the slot's current
+    /// occupant, or `None` if the handle is stale.
+    pub fn get_latch(&self, frame_id: BufferFrameIdT) -> Option<PageLatch> {
+        let (shard_idx, index, generation) = unpack_frame_id(frame_id);
+        let shard = self.shards.get(shard_idx as usize)?;
+        let state = shard.state.lock().unwrap();
+        if state.generations[index as usize] != generation {
+            return None;
+        }
+        Some(shard.frames[index as usize].clone())
+    }
+}
+
+/// Number of shards to split the frame pool into. Approximates the number of CPUs so shard-local
+/// contention stays low under concurrent access, falling back to a single shard if the platform
+/// can't report a parallelism hint.
+fn num_cpus() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allocate_and_deallocate_frame() {
+        let buffer = Buffer::with_shards(4, 2);
+
+        let mut frame_ids = Vec::new();
+        while let Some(frame_id) = buffer.allocate_frame() {
+            frame_ids.push(frame_id);
         }
-        Self { pool, page_table }
+        assert_eq!(frame_ids.len(), 4);
+        assert!(buffer.allocate_frame().is_none());
+
+        buffer.deallocate_frame(frame_ids[0]);
+        assert!(buffer.allocate_frame().is_some());
+    }
+
+    #[test]
+    fn test_get_latch_rejects_stale_frame_id() {
+        let buffer = Buffer::with_shards(1, 1);
+
+        let frame_id = buffer.allocate_frame().unwrap();
+        assert!(buffer.get_latch(frame_id).is_some());
+
+        buffer.deallocate_frame(frame_id);
+        assert!(buffer.get_latch(frame_id).is_none());
     }
 }
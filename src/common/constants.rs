@@ -7,3 +7,18 @@ pub type BlockIdT = u32;
 pub type RelationIdT = u32;
 pub type RecordIdT = u32;
 pub type BufferFrameIdT = u32;
+pub type PageIdT = u32;
+pub type LsnT = u64;
+pub type TransactionIdT = u32;
+
+/// The on-disk page size used by the disk manager. Kept distinct from `BLOCK_SIZE` even though
+/// the values match, since a "page" (raw disk manager unit) and a "block" (slotted storage
+/// layout) are different abstractions layered on top of each other.
+pub const PAGE_SIZE: u32 = BLOCK_SIZE;
+
+/// The id of the database's root catalog page. Allocated automatically when a new database file
+/// is created, so it is never handed out by `allocate_page()`.
+pub const CATALOG_ROOT_ID: PageIdT = 0;
+
+/// Sentinel value marking the end of the disk manager's on-disk free-list.
+pub const NIL_PAGE_ID: PageIdT = PageIdT::MAX;
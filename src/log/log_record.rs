@@ -0,0 +1,112 @@
+/*
+ * Copyright (c) 2021.  Shoyo Inokuchi.
+ * Please refer to github.com/shoyo/jindb for more information about this project and its license.
+ */
+
+use crate::common::constants::PageIdT;
+use crate::common::{LsnT, TransactionIdT};
+
+/// Sentinel `prev_lsn`/`undo_next_lsn` marking "no earlier record", i.e. the start of a
+/// transaction's log chain.
+pub const NIL_LSN: LsnT = 0;
+
+/// The kind of change a `LogRecord` describes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogRecordType {
+    Insert,
+    Update,
+    Delete,
+    Commit,
+    Abort,
+    /// A Compensation Log Record, written while undoing a loser transaction so that a crash
+    /// during recovery doesn't re-undo work that has already been undone.
+    CompensationLogRecord,
+}
+
+/// A single write-ahead log record. Records form a per-transaction singly-linked chain via
+/// `prev_lsn`, which `LogRecovery::undo` walks backward over.
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    pub lsn: LsnT,
+    pub prev_lsn: LsnT,
+    pub txn_id: TransactionIdT,
+    pub record_type: LogRecordType,
+    pub page_id: PageIdT,
+    pub before_image: Vec<u8>,
+    pub after_image: Vec<u8>,
+
+    /// Only set on `CompensationLogRecord`s: the LSN that undo should resume from next, which
+    /// skips over the record the CLR compensates for rather than re-undoing it.
+    pub undo_next_lsn: Option<LsnT>,
+}
+
+impl LogRecord {
+    /// Serialize this record as a single line of `log_buffer`: `|`-delimited fields with binary
+    /// images hex-encoded so the whole log can be kept as a plain `String`.
+    pub fn to_line(&self) -> String {
+        format!(
+            "{}|{}|{}|{}|{}|{}|{}|{}",
+            self.lsn,
+            self.prev_lsn,
+            self.txn_id,
+            record_type_to_str(self.record_type),
+            self.page_id,
+            encode_hex(&self.before_image),
+            encode_hex(&self.after_image),
+            self.undo_next_lsn.map_or("-".to_string(), |lsn| lsn.to_string()),
+        )
+    }
+
+    /// Parse a line previously produced by `to_line`.
+    pub fn from_line(line: &str) -> LogRecord {
+        let fields: Vec<&str> = line.split('|').collect();
+        LogRecord {
+            lsn: fields[0].parse().unwrap(),
+            prev_lsn: fields[1].parse().unwrap(),
+            txn_id: fields[2].parse().unwrap(),
+            record_type: record_type_from_str(fields[3]),
+            page_id: fields[4].parse().unwrap(),
+            before_image: decode_hex(fields[5]),
+            after_image: decode_hex(fields[6]),
+            undo_next_lsn: if fields[7] == "-" {
+                None
+            } else {
+                Some(fields[7].parse().unwrap())
+            },
+        }
+    }
+}
+
+fn record_type_to_str(record_type: LogRecordType) -> &'static str {
+    match record_type {
+        LogRecordType::Insert => "insert",
+        LogRecordType::Update => "update",
+        LogRecordType::Delete => "delete",
+        LogRecordType::Commit => "commit",
+        LogRecordType::Abort => "abort",
+        LogRecordType::CompensationLogRecord => "clr",
+    }
+}
+
+fn record_type_from_str(s: &str) -> LogRecordType {
+    match s {
+        "insert" => LogRecordType::Insert,
+        "update" => LogRecordType::Update,
+        "delete" => LogRecordType::Delete,
+        "commit" => LogRecordType::Commit,
+        "abort" => LogRecordType::Abort,
+        "clr" => LogRecordType::CompensationLogRecord,
+        other => panic!("unrecognized log record type: {}", other),
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(s: &str) -> Vec<u8> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+        .collect()
+}
@@ -0,0 +1,2 @@
+pub mod log_record;
+pub mod recovery;
@@ -1,12 +1,22 @@
 /*
- * Copyright (c) 2020.  Shoyo Inokuchi.
+ * Copyright (c) 2020 - 2021.  Shoyo Inokuchi.
  * Please refer to github.com/shoyo/jin for more information about this project and its license.
  */
 
+use crate::common::constants::{PageIdT, PAGE_SIZE};
 use crate::common::{LsnT, TransactionIdT};
+use crate::log::log_record::{LogRecord, LogRecordType, NIL_LSN};
+use crate::storage::disk_manager::DiskManager;
 use std::collections::HashMap;
+use std::convert::TryInto;
+
+/// Offset of the page header's `page_lsn` field, reserved at the front of every page. Redo
+/// compares a record's LSN against this to stay idempotent: a page whose `page_lsn` is already
+/// at or past a record's LSN has that change applied, so reapplying it is skipped.
+const PAGE_LSN_OFFSET: usize = 0;
 
 struct LogRecovery {
+    /// The entire write-ahead log, held as newline-delimited `LogRecord::to_line()` records.
     log_buffer: String,
 
     /// Mapping of active transactions to latest LSN
@@ -14,14 +24,263 @@ struct LogRecovery {
 
     /// Mapping of LSN to log file offset for undo operations
     lsn_offsets: HashMap<LsnT, i32>,
+
+    /// Mapping of dirty page to the LSN of the earliest log record that may have dirtied it,
+    /// built during the analysis pass and used to bound where redo needs to start from.
+    dirty_pages: HashMap<PageIdT, LsnT>,
 }
 
 impl LogRecovery {
-    pub fn redo() {
-        todo!()
+    pub fn new(log_buffer: String) -> Self {
+        Self {
+            log_buffer,
+            active: HashMap::new(),
+            lsn_offsets: HashMap::new(),
+            dirty_pages: HashMap::new(),
+        }
+    }
+
+    /// Parse `log_buffer` into its constituent records, alongside each record's byte offset.
+    fn records_with_offsets(&self) -> Vec<(i32, LogRecord)> {
+        let mut records = Vec::new();
+        let mut offset = 0;
+        for line in self.log_buffer.lines() {
+            records.push((offset, LogRecord::from_line(line)));
+            offset += (line.len() + 1) as i32;
+        }
+        records
+    }
+
+    /// Analysis pass: scan the log forward, rebuilding the active transaction table (latest LSN
+    /// per transaction) and dirty-page table, and filling `lsn_offsets` so any LSN can be seeked
+    /// to directly in a later pass.
+    pub fn analyze(&mut self) {
+        self.active.clear();
+        self.lsn_offsets.clear();
+        self.dirty_pages.clear();
+
+        for (offset, record) in self.records_with_offsets() {
+            self.lsn_offsets.insert(record.lsn, offset);
+
+            match record.record_type {
+                LogRecordType::Commit | LogRecordType::Abort => {
+                    self.active.remove(&record.txn_id);
+                }
+                _ => {
+                    self.active.insert(record.txn_id, record.lsn);
+                    self.dirty_pages
+                        .entry(record.page_id)
+                        .or_insert(record.lsn);
+                }
+            }
+        }
+    }
+
+    /// Redo pass: replay every update whose `after_image` is newer than the page's stamped
+    /// `page_lsn`, reading each page through the disk manager so recovery shares the same I/O
+    /// path as normal operation. Starts scanning from the earliest recLSN in `dirty_pages`
+    /// rather than the start of the log, since no record before that LSN could have dirtied a
+    /// page that wasn't already durable.
+    pub fn redo(&mut self, disk_manager: &DiskManager) {
+        let start_lsn = self.dirty_pages.values().copied().min().unwrap_or(NIL_LSN);
+
+        for (_, record) in self.records_with_offsets() {
+            if record.lsn < start_lsn {
+                continue;
+            }
+            if record.record_type == LogRecordType::Commit
+                || record.record_type == LogRecordType::Abort
+            {
+                continue;
+            }
+            if !self.dirty_pages.contains_key(&record.page_id) {
+                continue;
+            }
+
+            let mut page = vec![0; PAGE_SIZE as usize];
+            disk_manager.read_page(record.page_id, &mut page);
+
+            if read_page_lsn(&page) >= record.lsn {
+                // Already applied; reapplying would not be idempotent.
+                continue;
+            }
+
+            apply_image(&mut page, &record.after_image);
+            write_page_lsn(&mut page, record.lsn);
+            disk_manager.write_page(record.page_id, &page);
+        }
+    }
+
+    /// Undo pass: for each transaction still active after analysis (a "loser"), walk its log
+    /// chain backward via `prev_lsn`, restoring `before_image`s and emitting a CLR per undone
+    /// record. A CLR's `undo_next_lsn` points past the record it compensates for, so that if
+    /// recovery crashes again, a repeated undo pass skips work that is already compensated.
+    pub fn undo(&mut self, disk_manager: &DiskManager) {
+        let records = self.records_with_offsets();
+        let by_lsn: HashMap<LsnT, LogRecord> = records
+            .into_iter()
+            .map(|(_, record)| (record.lsn, record))
+            .collect();
+
+        let losers: Vec<TransactionIdT> = self.active.keys().copied().collect();
+
+        for txn_id in losers {
+            let mut cursor = self.active.get(&txn_id).copied();
+
+            while let Some(lsn) = cursor {
+                if lsn == NIL_LSN {
+                    break;
+                }
+                let record = match by_lsn.get(&lsn) {
+                    Some(record) => record.clone(),
+                    None => break,
+                };
+
+                // A CLR records work that is already undone; restoring its before-image would
+                // redo the original change, so only follow its `undo_next_lsn` past it.
+                if record.record_type == LogRecordType::CompensationLogRecord {
+                    cursor = record.undo_next_lsn.filter(|&next| next != NIL_LSN);
+                    continue;
+                }
+
+                let mut page = vec![0; PAGE_SIZE as usize];
+                disk_manager.read_page(record.page_id, &mut page);
+                apply_image(&mut page, &record.before_image);
+                write_page_lsn(&mut page, record.lsn);
+                disk_manager.write_page(record.page_id, &page);
+
+                let clr = LogRecord {
+                    lsn: next_lsn(&self.lsn_offsets),
+                    prev_lsn: record.lsn,
+                    txn_id: record.txn_id,
+                    record_type: LogRecordType::CompensationLogRecord,
+                    page_id: record.page_id,
+                    before_image: record.after_image.clone(),
+                    after_image: record.before_image.clone(),
+                    undo_next_lsn: Some(record.prev_lsn),
+                };
+                self.append(&clr);
+
+                // A CLR's `undo_next_lsn` already points past the record it compensates for, so
+                // resuming here must follow it instead of `prev_lsn` — otherwise a crash during
+                // recovery would walk back into an already-undone record via its `prev_lsn` and
+                // undo it a second time.
+                let next = record.undo_next_lsn.unwrap_or(record.prev_lsn);
+                cursor = if next == NIL_LSN { None } else { Some(next) };
+            }
+
+            self.active.remove(&txn_id);
+        }
     }
 
-    pub fn undo() {
-        todo!()
+    /// Append a record (typically a CLR) to the in-memory log buffer and index it.
+    fn append(&mut self, record: &LogRecord) {
+        let offset = self.log_buffer.len() as i32;
+        self.log_buffer.push_str(&record.to_line());
+        self.log_buffer.push('\n');
+        self.lsn_offsets.insert(record.lsn, offset);
+    }
+}
+
+fn next_lsn(lsn_offsets: &HashMap<LsnT, i32>) -> LsnT {
+    lsn_offsets.keys().copied().max().unwrap_or(NIL_LSN) + 1
+}
+
+fn read_page_lsn(page: &[u8]) -> LsnT {
+    let bytes: [u8; 8] = page[PAGE_LSN_OFFSET..PAGE_LSN_OFFSET + 8].try_into().unwrap();
+    LsnT::from_le_bytes(bytes)
+}
+
+fn write_page_lsn(page: &mut [u8], lsn: LsnT) {
+    page[PAGE_LSN_OFFSET..PAGE_LSN_OFFSET + 8].copy_from_slice(&lsn.to_le_bytes());
+}
+
+/// Overwrite the portion of `page` following the page header with `image`.
+fn apply_image(page: &mut [u8], image: &[u8]) {
+    let start = PAGE_LSN_OFFSET + 8;
+    let end = start + image.len();
+    page[start..end].copy_from_slice(image);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::disk_manager::DiskManager;
+    use std::fs;
+
+    #[test]
+    fn test_redo_reapplies_uncommitted_update() {
+        let filename = "RECOVERY_TEST_REDO";
+        let disk_manager = DiskManager::new(filename);
+        let page_id = disk_manager.allocate_page();
+
+        let record = LogRecord {
+            lsn: 1,
+            prev_lsn: NIL_LSN,
+            txn_id: 1,
+            record_type: LogRecordType::Update,
+            page_id,
+            before_image: vec![0; 4],
+            after_image: vec![9, 9, 9, 9],
+            undo_next_lsn: None,
+        };
+
+        let mut recovery = LogRecovery::new(format!("{}\n", record.to_line()));
+        recovery.analyze();
+        recovery.redo(&disk_manager);
+
+        let mut page = vec![0; PAGE_SIZE as usize];
+        disk_manager.read_page(page_id, &mut page);
+        assert_eq!(&page[8..12], &[9, 9, 9, 9]);
+        assert_eq!(read_page_lsn(&page), 1);
+
+        fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_undo_restores_before_image_and_stamps_clr_undo_next_lsn() {
+        let filename = "RECOVERY_TEST_UNDO";
+        let disk_manager = DiskManager::new(filename);
+        let page_id = disk_manager.allocate_page();
+
+        // Pretend the update was already applied, as if written before a crash, then undo the
+        // still-active (loser) transaction that wrote it.
+        let record = LogRecord {
+            lsn: 1,
+            prev_lsn: NIL_LSN,
+            txn_id: 1,
+            record_type: LogRecordType::Update,
+            page_id,
+            before_image: vec![1, 1, 1, 1],
+            after_image: vec![2, 2, 2, 2],
+            undo_next_lsn: None,
+        };
+        let mut page = vec![0; PAGE_SIZE as usize];
+        disk_manager.read_page(page_id, &mut page);
+        apply_image(&mut page, &record.after_image);
+        disk_manager.write_page(page_id, &page);
+
+        let mut recovery = LogRecovery::new(format!("{}\n", record.to_line()));
+        recovery.analyze();
+        assert!(recovery.active.contains_key(&1));
+
+        recovery.undo(&disk_manager);
+
+        let mut restored = vec![0; PAGE_SIZE as usize];
+        disk_manager.read_page(page_id, &mut restored);
+        assert_eq!(&restored[8..12], &[1, 1, 1, 1]);
+
+        // The appended CLR's undo_next_lsn must point at the undone record's prev_lsn (NIL_LSN
+        // here) so a repeated undo pass resumes past it instead of re-undoing it via prev_lsn.
+        let clr = recovery
+            .log_buffer
+            .lines()
+            .map(LogRecord::from_line)
+            .find(|r| r.record_type == LogRecordType::CompensationLogRecord)
+            .unwrap();
+        assert_eq!(clr.undo_next_lsn, Some(NIL_LSN));
+        assert!(!recovery.active.contains_key(&1));
+
+        fs::remove_file(filename).unwrap();
     }
 }
@@ -0,0 +1,216 @@
+/*
+ * Copyright (c) 2020 - 2021.  Shoyo Inokuchi.
+ * Please refer to github.com/shoyo/jindb for more information about this project and its license.
+ */
+
+use crate::common::constants::{CATALOG_ROOT_ID, NIL_PAGE_ID, PAGE_SIZE};
+use crate::common::PageIdT;
+use crate::bitmap::{clear_nth_bit, get_nth_bit, set_nth_bit};
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::Mutex;
+
+/// The free-list head is persisted in the first 4 bytes of the catalog root page, so the
+/// location survives a restart instead of living only in memory.
+const FREE_LIST_HEAD_OFFSET: u64 = 0;
+
+/// State that must be mutated atomically together: the bitmap tracking which pages are
+/// allocated, the on-disk file handle, and the high-water mark used to grow the file.
+struct Inner {
+    file: File,
+    bitmap: Vec<u64>,
+    num_pages: PageIdT,
+}
+
+impl Inner {
+    fn word_and_bit(page_id: PageIdT) -> (usize, u32) {
+        ((page_id / 64) as usize, page_id % 64)
+    }
+
+    fn is_allocated(&self, page_id: PageIdT) -> bool {
+        let (word, bit) = Self::word_and_bit(page_id);
+        match self.bitmap.get(word) {
+            Some(bits) => get_nth_bit(bits, bit).unwrap() == 1,
+            None => false,
+        }
+    }
+
+    fn set_allocated(&mut self, page_id: PageIdT) {
+        let (word, bit) = Self::word_and_bit(page_id);
+        while self.bitmap.len() <= word {
+            self.bitmap.push(0);
+        }
+        set_nth_bit(&mut self.bitmap[word], bit).unwrap();
+    }
+
+    fn clear_allocated(&mut self, page_id: PageIdT) {
+        let (word, bit) = Self::word_and_bit(page_id);
+        if let Some(bits) = self.bitmap.get_mut(word) {
+            clear_nth_bit(bits, bit).unwrap();
+        }
+    }
+
+    fn read_raw(&mut self, page_id: PageIdT, data: &mut [u8]) {
+        self.file
+            .seek(SeekFrom::Start((page_id * PAGE_SIZE) as u64))
+            .unwrap();
+        self.file.read_exact(data).unwrap();
+    }
+
+    fn write_raw(&mut self, page_id: PageIdT, data: &[u8]) {
+        self.file
+            .seek(SeekFrom::Start((page_id * PAGE_SIZE) as u64))
+            .unwrap();
+        self.file.write_all(data).unwrap();
+        self.file.flush().unwrap();
+    }
+
+    /// Read the free-list head pointer out of the catalog root page.
+    fn free_list_head(&mut self) -> PageIdT {
+        let mut page = vec![0; PAGE_SIZE as usize];
+        self.read_raw(CATALOG_ROOT_ID, &mut page);
+        let bytes: [u8; 4] = page[FREE_LIST_HEAD_OFFSET as usize..FREE_LIST_HEAD_OFFSET as usize + 4]
+            .try_into()
+            .unwrap();
+        PageIdT::from_le_bytes(bytes)
+    }
+
+    /// Overwrite the free-list head pointer stored in the catalog root page.
+    fn set_free_list_head(&mut self, page_id: PageIdT) {
+        let mut page = vec![0; PAGE_SIZE as usize];
+        self.read_raw(CATALOG_ROOT_ID, &mut page);
+        page[FREE_LIST_HEAD_OFFSET as usize..FREE_LIST_HEAD_OFFSET as usize + 4]
+            .copy_from_slice(&page_id.to_le_bytes());
+        self.write_raw(CATALOG_ROOT_ID, &page);
+    }
+}
+
+/// Manages reading/writing database pages to and from the database file on disk.
+pub struct DiskManager {
+    inner: Mutex<Inner>,
+}
+
+impl DiskManager {
+    /// Open (or create) the database file at `filename`, allocating the catalog root page and
+    /// initializing an empty free-list on first creation.
+    pub fn new(filename: &str) -> Self {
+        let is_new = !std::path::Path::new(filename).exists();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(filename)
+            .unwrap();
+
+        let mut inner = Inner {
+            file,
+            bitmap: Vec::new(),
+            num_pages: CATALOG_ROOT_ID + 1,
+        };
+
+        if is_new {
+            inner.set_allocated(CATALOG_ROOT_ID);
+            inner.write_raw(CATALOG_ROOT_ID, &vec![0; PAGE_SIZE as usize]);
+            inner.set_free_list_head(NIL_PAGE_ID);
+        } else {
+            // The bitmap itself isn't persisted, only the free-list head is. Rebuild it by
+            // marking every page up to the file's high-water mark allocated, then walking the
+            // on-disk free-list and clearing each page threaded onto it, so a reopened file ends
+            // up with the same allocation state it had before the restart.
+            let file_len = inner.file.metadata().unwrap().len();
+            inner.num_pages = (file_len / PAGE_SIZE as u64) as PageIdT;
+            for page_id in 0..inner.num_pages {
+                inner.set_allocated(page_id);
+            }
+
+            let mut cursor = inner.free_list_head();
+            while cursor != NIL_PAGE_ID {
+                inner.clear_allocated(cursor);
+                let mut page = vec![0; PAGE_SIZE as usize];
+                inner.read_raw(cursor, &mut page);
+                let next: [u8; 4] = page[0..4].try_into().unwrap();
+                cursor = PageIdT::from_le_bytes(next);
+            }
+        }
+
+        Self {
+            inner: Mutex::new(inner),
+        }
+    }
+
+    /// Return whether the given page id is currently allocated.
+    pub fn is_allocated(&self, page_id: PageIdT) -> bool {
+        let inner = self.inner.lock().unwrap();
+        inner.is_allocated(page_id)
+    }
+
+    /// Allocate a new page, reusing a freed page from the free-list if one is available, and
+    /// only extending the file when the free-list is empty.
+    pub fn allocate_page(&self) -> PageIdT {
+        let mut inner = self.inner.lock().unwrap();
+
+        let head = inner.free_list_head();
+        if head != NIL_PAGE_ID {
+            let mut freed_page = vec![0; PAGE_SIZE as usize];
+            inner.read_raw(head, &mut freed_page);
+            let next: [u8; 4] = freed_page[0..4].try_into().unwrap();
+            inner.set_free_list_head(PageIdT::from_le_bytes(next));
+            inner.set_allocated(head);
+            // The page still has its free-list "next" pointer (and whatever else was there
+            // before `deallocate_page`) sitting in it; zero it so callers that read a freshly
+            // reused page before their first `write_page` see a clean page, not a stale one.
+            inner.write_raw(head, &vec![0; PAGE_SIZE as usize]);
+            return head;
+        }
+
+        let page_id = inner.num_pages;
+        inner.num_pages += 1;
+        inner.set_allocated(page_id);
+        inner.write_raw(page_id, &vec![0; PAGE_SIZE as usize]);
+        page_id
+    }
+
+    /// Free a previously-allocated page, threading it onto the head of the on-disk free-list so
+    /// a future `allocate_page()` call can reuse it.
+    pub fn deallocate_page(&self, page_id: PageIdT) {
+        let mut inner = self.inner.lock().unwrap();
+        assert!(
+            inner.is_allocated(page_id),
+            "cannot deallocate page {} that isn't allocated",
+            page_id
+        );
+
+        let head = inner.free_list_head();
+        let mut page = vec![0; PAGE_SIZE as usize];
+        page[0..4].copy_from_slice(&head.to_le_bytes());
+        inner.write_raw(page_id, &page);
+
+        inner.clear_allocated(page_id);
+        inner.set_free_list_head(page_id);
+    }
+
+    /// Read the page with the given id from disk into `data`. Panics if the page isn't allocated.
+    pub fn read_page(&self, page_id: PageIdT, data: &mut [u8]) {
+        let mut inner = self.inner.lock().unwrap();
+        assert!(inner.is_allocated(page_id), "page {} isn't allocated", page_id);
+        inner.read_raw(page_id, data);
+    }
+
+    /// Write `data` to the page with the given id on disk. Panics if the page isn't allocated.
+    pub fn write_page(&self, page_id: PageIdT, data: &[u8]) {
+        let mut inner = self.inner.lock().unwrap();
+        assert!(inner.is_allocated(page_id), "page {} isn't allocated", page_id);
+        inner.write_raw(page_id, data);
+    }
+}
+
+/// Open the database file at `filename` for writing, bypassing the disk manager. Intended for
+/// tests that need to assert on the file's raw contents.
+pub fn open_write_file(filename: &str) -> File {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(filename)
+        .unwrap()
+}
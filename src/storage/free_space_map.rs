@@ -0,0 +1,413 @@
+/*
+ * Copyright (c) 2021.  Shoyo Inokuchi.
+ * Please refer to github.com/shoyo/jindb for more information about this project and its license.
+ */
+
+use crate::common::constants::{BlockIdT, RelationIdT, BLOCK_SIZE, PAGE_SIZE};
+use crate::storage::disk_manager::DiskManager;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::sync::{Arc, Mutex};
+
+/// Header: `level` (1 byte) + `num_entries` (4 bytes).
+const HEADER_SIZE: usize = 5;
+/// Each entry is a quantized free-space code (1 byte) plus the block id it describes (4 bytes).
+const ENTRY_SIZE: usize = 5;
+
+fn max_entries_per_fsm_block() -> usize {
+    (PAGE_SIZE as usize - HEADER_SIZE) / ENTRY_SIZE
+}
+
+/// Quantize `free_bytes` into a single byte, in units of 1/256th of a block.
+fn quantize(free_bytes: u32) -> u8 {
+    let code = (free_bytes as u64 * 256 / BLOCK_SIZE as u64).min(255);
+    code as u8
+}
+
+struct FsmBlock {
+    /// `0` for a leaf, whose entries point at data blocks; `>0` for an internal node, whose
+    /// entries point at child FSM blocks of lower levels. A leaf attached under a root of level 2
+    /// or more sits directly beneath that root, not one level down.
+    level: u8,
+    /// `(code, block_id)` pairs. On a leaf, `code` is the block's own quantized free space. On an
+    /// internal node, `code` is the max free-space code anywhere in that child's subtree, so a
+    /// search can skip subtrees that can't possibly satisfy the request.
+    entries: Vec<(u8, BlockIdT)>,
+}
+
+impl FsmBlock {
+    fn empty(level: u8) -> Self {
+        Self {
+            level,
+            entries: Vec::new(),
+        }
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut page = vec![0; PAGE_SIZE as usize];
+        page[0] = self.level;
+        page[1..5].copy_from_slice(&(self.entries.len() as u32).to_le_bytes());
+        for (i, (code, block_id)) in self.entries.iter().enumerate() {
+            let offset = HEADER_SIZE + i * ENTRY_SIZE;
+            page[offset] = *code;
+            page[offset + 1..offset + 5].copy_from_slice(&block_id.to_le_bytes());
+        }
+        page
+    }
+
+    fn deserialize(page: &[u8]) -> Self {
+        let level = page[0];
+        let num_entries = u32::from_le_bytes(page[1..5].try_into().unwrap()) as usize;
+        let mut entries = Vec::with_capacity(num_entries);
+        for i in 0..num_entries {
+            let offset = HEADER_SIZE + i * ENTRY_SIZE;
+            let code = page[offset];
+            let block_id = u32::from_le_bytes(page[offset + 1..offset + 5].try_into().unwrap());
+            entries.push((code, block_id));
+        }
+        Self { level, entries }
+    }
+
+    /// The summary code this block should report to its parent: the max among its own entries.
+    fn summary(&self) -> u8 {
+        self.entries.iter().map(|(code, _)| *code).max().unwrap_or(0)
+    }
+
+    fn is_full(&self) -> bool {
+        self.entries.len() >= max_entries_per_fsm_block()
+    }
+}
+
+/// A Free Space Map, tracking how much free space each data block in a relation has without
+/// requiring a linear scan of the relation's actual blocks. Each relation's map is a tree of
+/// dedicated FSM blocks: leaves hold one quantized byte per data block, and internal nodes hold
+/// one quantized byte per child summarizing that child's best offer, so `fsm_find_block` descends
+/// rather than scanning every data block. New leaves are appended under the root as the relation
+/// grows, and once a node that needs a new child is itself full, a new root is grown above it —
+/// so registering a block never silently evicts another block's entry, unlike scanning a single
+/// fixed-size leaf. A new root is also grown whenever the root's level is above 1, so once the
+/// first level-1 root is full, each further leaf adds one level to the tree instead of being
+/// placed under an existing internal node.
+pub struct FreeSpaceMap {
+    /// Root FSM block id per relation, created lazily on first use.
+    roots: Mutex<HashMap<RelationIdT, BlockIdT>>,
+    /// Which relation owns each data block, recorded on registration. `fsm_update` finds a
+    /// block's leaf through `leaf_of`, not through this map.
+    owners: Mutex<HashMap<BlockIdT, RelationIdT>>,
+    /// The leaf FSM block currently holding each data block's entry.
+    leaf_of: Mutex<HashMap<BlockIdT, BlockIdT>>,
+    /// The leaf currently being appended to for each relation, i.e. the rightmost leaf in that
+    /// relation's tree. Once full, registering another block grows a new leaf and attaches it to
+    /// the tree.
+    append_leaf: Mutex<HashMap<RelationIdT, BlockIdT>>,
+    /// Parent FSM block id of each non-root FSM block, so a leaf (or internal node) whose
+    /// contents changed can walk back up and refresh every ancestor's summary of it.
+    parent_of: Mutex<HashMap<BlockIdT, BlockIdT>>,
+    /// One lock per FSM block, guarding that block's on-disk read-modify-write so concurrent
+    /// registrations/updates/summary-refreshes against the same block don't race and silently
+    /// clobber each other's change. Created lazily the first time a block is touched.
+    block_locks: Mutex<HashMap<BlockIdT, Arc<Mutex<()>>>>,
+}
+
+impl FreeSpaceMap {
+    pub fn new() -> Self {
+        Self {
+            roots: Mutex::new(HashMap::new()),
+            owners: Mutex::new(HashMap::new()),
+            leaf_of: Mutex::new(HashMap::new()),
+            append_leaf: Mutex::new(HashMap::new()),
+            parent_of: Mutex::new(HashMap::new()),
+            block_locks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Get (creating if necessary) the lock guarding `block_id`'s on-disk read-modify-write.
+    fn block_lock(&self, block_id: BlockIdT) -> Arc<Mutex<()>> {
+        self.block_locks
+            .lock()
+            .unwrap()
+            .entry(block_id)
+            .or_insert_with(|| Arc::new(Mutex::new(())))
+            .clone()
+    }
+
+    fn read_fsm_block(disk_manager: &DiskManager, block_id: BlockIdT) -> FsmBlock {
+        let mut page = vec![0; PAGE_SIZE as usize];
+        disk_manager.read_page(block_id, &mut page);
+        FsmBlock::deserialize(&page)
+    }
+
+    fn write_fsm_block(disk_manager: &DiskManager, block_id: BlockIdT, block: &FsmBlock) {
+        disk_manager.write_page(block_id, &block.serialize());
+    }
+
+    fn new_fsm_block(disk_manager: &DiskManager, level: u8) -> BlockIdT {
+        let id = disk_manager.allocate_page();
+        Self::write_fsm_block(disk_manager, id, &FsmBlock::empty(level));
+        id
+    }
+
+    /// Register a newly-allocated data block with the map, under the given relation, with its
+    /// initial free space. Grows the relation's FSM tree with a new leaf (and, if necessary, a
+    /// new root above it) when the currently-appended-to leaf is full.
+    pub fn fsm_register_block(
+        &self,
+        disk_manager: &DiskManager,
+        relation_id: RelationIdT,
+        block_id: BlockIdT,
+        free_remaining: u32,
+    ) {
+        self.owners.lock().unwrap().insert(block_id, relation_id);
+
+        loop {
+            let leaf_id = self.leaf_for_append(disk_manager, relation_id);
+            let lock = self.block_lock(leaf_id);
+            let _guard = lock.lock().unwrap();
+
+            // The leaf may have filled up since `leaf_for_append` released its locks (another
+            // thread's append raced ahead of this one); retry so the next iteration grows a
+            // fresh leaf instead of overflowing this one.
+            let mut leaf = Self::read_fsm_block(disk_manager, leaf_id);
+            if leaf.is_full() {
+                continue;
+            }
+
+            leaf.entries.push((quantize(free_remaining), block_id));
+            Self::write_fsm_block(disk_manager, leaf_id, &leaf);
+
+            self.leaf_of.lock().unwrap().insert(block_id, leaf_id);
+            self.refresh_ancestor_summaries(disk_manager, leaf_id);
+            return;
+        }
+    }
+
+    /// Return the leaf currently being appended to for `relation_id`, creating the relation's
+    /// first leaf (and root) if none exists yet, or growing the tree with a fresh leaf if the
+    /// current one is full.
+    fn leaf_for_append(&self, disk_manager: &DiskManager, relation_id: RelationIdT) -> BlockIdT {
+        let mut roots = self.roots.lock().unwrap();
+        let mut append_leaf = self.append_leaf.lock().unwrap();
+
+        if !roots.contains_key(&relation_id) {
+            let leaf_id = Self::new_fsm_block(disk_manager, 0);
+            roots.insert(relation_id, leaf_id);
+            append_leaf.insert(relation_id, leaf_id);
+            return leaf_id;
+        }
+
+        let leaf_id = append_leaf[&relation_id];
+        if !Self::read_fsm_block(disk_manager, leaf_id).is_full() {
+            return leaf_id;
+        }
+
+        let new_leaf_id = Self::new_fsm_block(disk_manager, 0);
+        self.attach_child(disk_manager, &mut roots, relation_id, new_leaf_id, 0);
+        append_leaf.insert(relation_id, new_leaf_id);
+        new_leaf_id
+    }
+
+    /// Attach `child_id` (at tree depth `child_level`) as a child of `relation_id`'s root,
+    /// growing a new root above the current one first if the current root is itself full or at
+    /// the wrong level to hold it directly.
+    fn attach_child(
+        &self,
+        disk_manager: &DiskManager,
+        roots: &mut HashMap<RelationIdT, BlockIdT>,
+        relation_id: RelationIdT,
+        child_id: BlockIdT,
+        child_level: u8,
+    ) {
+        let root_id = roots[&relation_id];
+        let lock = self.block_lock(root_id);
+        let _guard = lock.lock().unwrap();
+        let root = Self::read_fsm_block(disk_manager, root_id);
+        let child_summary = Self::read_fsm_block(disk_manager, child_id).summary();
+
+        if root.level == child_level + 1 && !root.is_full() {
+            let mut root = root;
+            root.entries.push((child_summary, child_id));
+            Self::write_fsm_block(disk_manager, root_id, &root);
+            self.parent_of.lock().unwrap().insert(child_id, root_id);
+            return;
+        }
+
+        // The current root can't take the child directly (it's full, or it's a bare leaf that
+        // needs to become an internal node first): grow a new root one level higher, with the
+        // old root and the new child as its first two entries.
+        let root_summary = root.summary();
+        let new_root = FsmBlock {
+            level: root.level.max(child_level) + 1,
+            entries: vec![(root_summary, root_id), (child_summary, child_id)],
+        };
+        let new_root_id = disk_manager.allocate_page();
+        Self::write_fsm_block(disk_manager, new_root_id, &new_root);
+
+        let mut parent_of = self.parent_of.lock().unwrap();
+        parent_of.insert(root_id, new_root_id);
+        parent_of.insert(child_id, new_root_id);
+
+        roots.insert(relation_id, new_root_id);
+    }
+
+    /// After a leaf's contents changed, walk back up the `parent_of` chain recomputing each
+    /// ancestor's recorded summary for the child beneath it, so internal nodes never go stale.
+    fn refresh_ancestor_summaries(&self, disk_manager: &DiskManager, mut node_id: BlockIdT) {
+        loop {
+            let parent_id = match self.parent_of.lock().unwrap().get(&node_id).copied() {
+                Some(id) => id,
+                None => return,
+            };
+
+            let node_summary = Self::read_fsm_block(disk_manager, node_id).summary();
+
+            // Siblings of `node_id` may be refreshing the same parent concurrently; hold the
+            // parent's own lock across its read-modify-write so their summary updates don't race.
+            let lock = self.block_lock(parent_id);
+            let _guard = lock.lock().unwrap();
+            let mut parent = Self::read_fsm_block(disk_manager, parent_id);
+            if let Some(entry) = parent.entries.iter_mut().find(|(_, id)| *id == node_id) {
+                entry.0 = node_summary;
+            }
+            Self::write_fsm_block(disk_manager, parent_id, &parent);
+
+            node_id = parent_id;
+        }
+    }
+
+    /// Find a data block belonging to `relation_id` whose quantized free space is at least that of
+    /// `needed_bytes`, without scanning actual data blocks: descends the relation's FSM tree, at
+    /// each internal level following the first child whose summary code can satisfy the request.
+    /// Both sides are rounded down to 1/256ths of a block, so for a request smaller than a block
+    /// the block returned may have up to `BLOCK_SIZE / 256 - 1` bytes less free than
+    /// `needed_bytes`; a request of a whole block or more matches any block with code 255.
+    pub fn fsm_find_block(
+        &self,
+        disk_manager: &DiskManager,
+        relation_id: RelationIdT,
+        needed_bytes: u32,
+    ) -> Option<BlockIdT> {
+        let needed_code = quantize(needed_bytes);
+        let root_id = *self.roots.lock().unwrap().get(&relation_id)?;
+
+        let mut node_id = root_id;
+        loop {
+            let node = Self::read_fsm_block(disk_manager, node_id);
+            let candidate = node.entries.iter().find(|(code, _)| *code >= needed_code)?;
+
+            if node.level == 0 {
+                return Some(candidate.1);
+            }
+            node_id = candidate.1;
+        }
+    }
+
+    /// Update the quantized free-space code recorded for `block_id`, called whenever a record is
+    /// inserted into or deleted from that block.
+    pub fn fsm_update(&self, disk_manager: &DiskManager, block_id: BlockIdT, free_remaining: u32) {
+        let leaf_id = match self.leaf_of.lock().unwrap().get(&block_id).copied() {
+            Some(id) => id,
+            None => return,
+        };
+
+        let lock = self.block_lock(leaf_id);
+        let _guard = lock.lock().unwrap();
+
+        let mut leaf = Self::read_fsm_block(disk_manager, leaf_id);
+        if let Some(entry) = leaf.entries.iter_mut().find(|(_, id)| *id == block_id) {
+            entry.0 = quantize(free_remaining);
+        }
+        Self::write_fsm_block(disk_manager, leaf_id, &leaf);
+
+        self.refresh_ancestor_summaries(disk_manager, leaf_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_find_after_update() {
+        let filename = "FSM_TEST_FIND_AFTER_UPDATE";
+        let disk_manager = DiskManager::new(filename);
+        let fsm = FreeSpaceMap::new();
+
+        let block_id = disk_manager.allocate_page();
+        fsm.fsm_register_block(&disk_manager, 1, block_id, 100);
+        assert_eq!(fsm.fsm_find_block(&disk_manager, 1, 3000), None);
+
+        fsm.fsm_update(&disk_manager, block_id, 3000);
+        assert_eq!(fsm.fsm_find_block(&disk_manager, 1, 3000), Some(block_id));
+
+        fsm.fsm_update(&disk_manager, block_id, 50);
+        assert_eq!(fsm.fsm_find_block(&disk_manager, 1, 3000), None);
+
+        fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    /// A relation with more data blocks than fit in a single leaf must grow the tree with an
+    /// extra leaf rather than silently dropping earlier blocks' entries.
+    fn test_registrations_past_one_leaf_are_not_lost() {
+        let filename = "FSM_TEST_TREE_GROWTH";
+        let disk_manager = DiskManager::new(filename);
+        let fsm = FreeSpaceMap::new();
+
+        let leaf_capacity = max_entries_per_fsm_block();
+        let num_blocks = leaf_capacity + 5;
+
+        let mut block_ids = Vec::with_capacity(num_blocks);
+        for _ in 0..num_blocks {
+            let block_id = disk_manager.allocate_page();
+            fsm.fsm_register_block(&disk_manager, 1, block_id, 10);
+            block_ids.push(block_id);
+        }
+
+        // Give the very first block registered (which would have been evicted by the old
+        // single-leaf implementation once the leaf filled up) a distinctive free-space value and
+        // confirm it's still findable.
+        fsm.fsm_update(&disk_manager, block_ids[0], 4096);
+        assert_eq!(fsm.fsm_find_block(&disk_manager, 1, 4000), Some(block_ids[0]));
+
+        fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    /// Concurrent registrations against the same relation must all land: the leaf selected by
+    /// `leaf_for_append` is a snapshot, so without a lock held across the actual read-modify-write
+    /// of that leaf, two threads can both append to their stale copy and one write clobbers the
+    /// other's entry.
+    fn test_concurrent_registrations_are_not_lost() {
+        let filename = "FSM_TEST_CONCURRENT_REGISTER";
+        let disk_manager = Arc::new(DiskManager::new(filename));
+        let fsm = Arc::new(FreeSpaceMap::new());
+        let num_blocks = 50;
+
+        let handles: Vec<_> = (0..num_blocks)
+            .map(|_| {
+                let disk_manager = disk_manager.clone();
+                let fsm = fsm.clone();
+                thread::spawn(move || {
+                    let block_id = disk_manager.allocate_page();
+                    fsm.fsm_register_block(&disk_manager, 1, block_id, 10);
+                    block_id
+                })
+            })
+            .collect();
+
+        let block_ids: Vec<BlockIdT> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        // Every registered block must still be findable: give each a distinctive free-space
+        // value in turn and confirm a lookup for it resolves to exactly that block.
+        for &block_id in &block_ids {
+            fsm.fsm_update(&disk_manager, block_id, 4096);
+            assert_eq!(fsm.fsm_find_block(&disk_manager, 1, 4000), Some(block_id));
+            fsm.fsm_update(&disk_manager, block_id, 10);
+        }
+
+        fs::remove_file(filename).unwrap();
+    }
+}
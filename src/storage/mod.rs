@@ -1,6 +1,7 @@
 pub mod block;
 pub mod constants;
 pub mod disk_manager;
+pub mod free_space_map;
 pub mod record;
 pub mod relation;
 pub mod schema;
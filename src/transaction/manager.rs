@@ -0,0 +1,399 @@
+/*
+ * Copyright (c) 2021.  Shoyo Inokuchi.
+ * Please refer to github.com/shoyo/jindb for more information about this project and its license.
+ */
+
+use crate::common::constants::PAGE_SIZE;
+use crate::common::{LsnT, PageIdT, TransactionIdT};
+use crate::log::log_record::{LogRecord, LogRecordType, NIL_LSN};
+use crate::storage::disk_manager::DiskManager;
+use crate::transaction::snapshot::Snapshot;
+use crate::transaction::version_store::VersionStore;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// A page a transaction has read or written, held privately until commit publishes it to the
+/// shared `VersionStore`.
+struct CowPage {
+    data: Vec<u8>,
+    /// The `xmin` of the version this page was copy-on-written from, so commit knows exactly
+    /// which version to stamp `xmax` on. `None` for a page this transaction freshly allocated,
+    /// which therefore has no prior version to supersede.
+    base_xmin: Option<TransactionIdT>,
+    /// Whether this page was freshly allocated by this transaction (as opposed to an
+    /// existing page copied on read), so abort knows to return it to the disk manager's
+    /// free-list instead of just discarding the private copy.
+    newly_allocated: bool,
+    /// Whether this transaction has actually written to its private copy. A page that was only
+    /// read is never published on commit — publishing it would fabricate a version nobody wrote.
+    dirty: bool,
+}
+
+/// A single transaction's private workspace: its id, the snapshot it reads through, and the
+/// copy-on-write pages it has touched so far. Writes are never applied in place — they accumulate
+/// here and are only published as new versions, visible to later snapshots, on `commit`.
+pub struct Transaction {
+    pub id: TransactionIdT,
+    pub snapshot: Snapshot,
+    private_pages: Mutex<HashMap<PageIdT, CowPage>>,
+}
+
+impl Transaction {
+    /// Read `page_id` as of this transaction's snapshot. The first read of a page resolves the
+    /// version visible to `self.snapshot` from the shared `VersionStore` (which may not be the
+    /// latest committed version) and stages a private copy; later reads within the same
+    /// transaction return that same staged copy, so a transaction always sees its own writes.
+    pub fn read_page(
+        &self,
+        version_store: &VersionStore,
+        disk_manager: &DiskManager,
+        page_id: PageIdT,
+    ) -> Vec<u8> {
+        let mut pages = self.private_pages.lock().unwrap();
+        if let Some(cow) = pages.get(&page_id) {
+            return cow.data.clone();
+        }
+
+        let (base_xmin, data) = version_store.visible_version(disk_manager, page_id, &self.snapshot);
+        pages.insert(
+            page_id,
+            CowPage {
+                data: data.clone(),
+                base_xmin: Some(base_xmin),
+                newly_allocated: false,
+                dirty: false,
+            },
+        );
+        data
+    }
+
+    /// Allocate a brand-new page, private to this transaction until commit publishes it as a
+    /// version with no predecessor.
+    pub fn allocate_page(&self, disk_manager: &DiskManager) -> PageIdT {
+        let page_id = disk_manager.allocate_page();
+        self.private_pages.lock().unwrap().insert(
+            page_id,
+            CowPage {
+                data: vec![0; PAGE_SIZE as usize],
+                base_xmin: None,
+                newly_allocated: true,
+                dirty: true,
+            },
+        );
+        page_id
+    }
+
+    /// Overwrite this transaction's private copy of `page_id` with `data`, marking it dirty so
+    /// `commit` publishes a new version of it. Reads `page_id` in first via `read_page` if this
+    /// transaction hasn't touched it yet, so the new version still records the correct
+    /// predecessor to supersede.
+    pub fn write_page(
+        &self,
+        version_store: &VersionStore,
+        disk_manager: &DiskManager,
+        page_id: PageIdT,
+        data: Vec<u8>,
+    ) {
+        if !self.private_pages.lock().unwrap().contains_key(&page_id) {
+            self.read_page(version_store, disk_manager, page_id);
+        }
+
+        let mut pages = self.private_pages.lock().unwrap();
+        let cow = pages.get_mut(&page_id).unwrap();
+        cow.data = data;
+        cow.dirty = true;
+    }
+}
+
+/// Coordinates multi-version concurrency control: assigning transaction ids and snapshots,
+/// tracking which transactions are in progress, and publishing or discarding a transaction's
+/// copy-on-write pages on commit/abort. Readers never block writers and vice versa, since a
+/// reader's snapshot is fixed at `begin()` and writers only ever append new page versions to the
+/// `VersionStore` rather than overwriting a page readers might still need.
+pub struct TransactionManager {
+    next_xid: Mutex<TransactionIdT>,
+    /// Every currently-active transaction's own snapshot, keyed by its id. Keeping the whole
+    /// snapshot (not just the id) around is what lets `is_vacuumable` compute the true horizon:
+    /// a transaction that committed can still be invisible to an active transaction whose
+    /// snapshot's `in_progress` set captured it before it committed.
+    active: Mutex<HashMap<TransactionIdT, Snapshot>>,
+    log: Mutex<String>,
+    disk_manager: Arc<DiskManager>,
+    version_store: VersionStore,
+}
+
+impl TransactionManager {
+    pub fn new(disk_manager: Arc<DiskManager>) -> Self {
+        Self {
+            next_xid: Mutex::new(1),
+            active: Mutex::new(HashMap::new()),
+            log: Mutex::new(String::new()),
+            disk_manager,
+            version_store: VersionStore::new(),
+        }
+    }
+
+    /// Begin a new transaction: assign it the next monotonically increasing id, and snapshot the
+    /// set of transactions currently in progress (and therefore invisible to it) below that id.
+    pub fn begin(&self) -> Transaction {
+        let mut next_xid = self.next_xid.lock().unwrap();
+        let id = *next_xid;
+        *next_xid += 1;
+
+        let mut active = self.active.lock().unwrap();
+        let snapshot = Snapshot {
+            xid: id,
+            xid_high_water: id,
+            in_progress: active.keys().copied().collect(),
+        };
+        active.insert(id, snapshot.clone());
+
+        Transaction {
+            id,
+            snapshot,
+            private_pages: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn read_page(&self, txn: &Transaction, page_id: PageIdT) -> Vec<u8> {
+        txn.read_page(&self.version_store, &self.disk_manager, page_id)
+    }
+
+    pub fn write_page(&self, txn: &Transaction, page_id: PageIdT, data: Vec<u8>) {
+        txn.write_page(&self.version_store, &self.disk_manager, page_id, data)
+    }
+
+    /// Commit a transaction: publish every page it actually wrote as a new version in the shared
+    /// `VersionStore`, append a commit record to the in-memory write-ahead log (the log is not
+    /// written to disk), and drop it from the active set so later snapshots see it as committed.
+    ///
+    /// If another transaction has already committed a newer version of any page this transaction
+    /// wrote (first-committer-wins), nothing is published, the transaction is aborted instead
+    /// (the same cleanup as `abort`), and `Err` is returned with the id of the transaction it
+    /// conflicted with.
+    pub fn commit(&self, txn: Transaction) -> Result<(), TransactionIdT> {
+        let pages = txn.private_pages.into_inner().unwrap();
+        let newly_allocated: Vec<PageIdT> = pages
+            .iter()
+            .filter(|(_, cow)| cow.newly_allocated)
+            .map(|(&page_id, _)| page_id)
+            .collect();
+        let writes: Vec<(PageIdT, Option<TransactionIdT>, Vec<u8>)> = pages
+            .into_iter()
+            .filter(|(_, cow)| cow.dirty)
+            .map(|(page_id, cow)| (page_id, cow.base_xmin, cow.data))
+            .collect();
+
+        if let Err(conflicting_txn) = self.version_store.publish_all(txn.id, writes) {
+            for page_id in newly_allocated {
+                self.disk_manager.deallocate_page(page_id);
+            }
+            self.append_txn_record(txn.id, LogRecordType::Abort);
+            self.active.lock().unwrap().remove(&txn.id);
+            return Err(conflicting_txn);
+        }
+
+        self.append_txn_record(txn.id, LogRecordType::Commit);
+        self.active.lock().unwrap().remove(&txn.id);
+        Ok(())
+    }
+
+    /// Abort a transaction: return any pages it freshly allocated to the disk manager's
+    /// free-list, discard its remaining copy-on-write pages unpublished, and drop it from the
+    /// active set.
+    pub fn abort(&self, txn: Transaction) {
+        let pages = txn.private_pages.into_inner().unwrap();
+        for (page_id, cow) in &pages {
+            if cow.newly_allocated {
+                self.disk_manager.deallocate_page(*page_id);
+            }
+        }
+
+        self.append_txn_record(txn.id, LogRecordType::Abort);
+        self.active.lock().unwrap().remove(&txn.id);
+    }
+
+    /// Reclaim versions whose `xmax` predates every still-live snapshot. Callers run this
+    /// on-access or from a background task.
+    pub fn vacuum(&self) {
+        self.version_store.vacuum(|xmax| self.is_vacuumable(xmax));
+    }
+
+    /// Whether a version deleted by transaction `xmax` is safe to reclaim: true once no
+    /// still-active transaction's snapshot could possibly see it. That horizon is *not* just the
+    /// oldest active transaction's own id — a transaction can commit and be removed from `active`
+    /// while another still-active transaction's snapshot captured it as in-progress (and
+    /// therefore still invisible) before it committed, so the horizon must also account for every
+    /// active snapshot's `in_progress` set, not just the active ids themselves.
+    fn is_vacuumable(&self, xmax: TransactionIdT) -> bool {
+        let active = self.active.lock().unwrap();
+        let horizon = active
+            .values()
+            .flat_map(|snapshot| std::iter::once(snapshot.xid).chain(snapshot.in_progress.iter().copied()))
+            .min();
+
+        match horizon {
+            Some(horizon) => xmax < horizon,
+            None => true,
+        }
+    }
+
+    fn append_txn_record(&self, txn_id: TransactionIdT, record_type: LogRecordType) {
+        let mut log = self.log.lock().unwrap();
+        let lsn = log.lines().count() as LsnT + 1;
+        // Commit/abort records aren't tied to a particular page, so `page_id` is unused here.
+        let record = LogRecord {
+            lsn,
+            prev_lsn: NIL_LSN,
+            txn_id,
+            record_type,
+            page_id: 0,
+            before_image: Vec::new(),
+            after_image: Vec::new(),
+            undo_next_lsn: None,
+        };
+        log.push_str(&record.to_line());
+        log.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn temp_manager(filename: &str) -> TransactionManager {
+        TransactionManager::new(Arc::new(DiskManager::new(filename)))
+    }
+
+    #[test]
+    fn test_read_own_write_before_commit() {
+        let filename = "TXN_TEST_READ_OWN_WRITE";
+        let manager = temp_manager(filename);
+
+        let txn = manager.begin();
+        let page_id = txn.allocate_page(&manager.disk_manager);
+        manager.write_page(&txn, page_id, vec![7; PAGE_SIZE as usize]);
+
+        assert_eq!(manager.read_page(&txn, page_id), vec![7; PAGE_SIZE as usize]);
+        manager.commit(txn).unwrap();
+
+        fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    /// The headline MVCC invariant: a transaction whose snapshot predates a writer's commit must
+    /// keep seeing the pre-commit version, even after the writer commits and a *new* transaction
+    /// sees the update.
+    fn test_snapshot_isolation_across_concurrent_commit() {
+        let filename = "TXN_TEST_SNAPSHOT_ISOLATION";
+        let manager = temp_manager(filename);
+
+        let setup_txn = manager.begin();
+        let page_id = setup_txn.allocate_page(&manager.disk_manager);
+        manager.write_page(&setup_txn, page_id, vec![1; PAGE_SIZE as usize]);
+        manager.commit(setup_txn).unwrap();
+
+        let reader = manager.begin();
+        assert_eq!(manager.read_page(&reader, page_id), vec![1; PAGE_SIZE as usize]);
+
+        let writer = manager.begin();
+        manager.write_page(&writer, page_id, vec![2; PAGE_SIZE as usize]);
+        manager.commit(writer).unwrap();
+
+        // `reader`'s snapshot predates `writer`'s commit, so it must still see the old version.
+        assert_eq!(manager.read_page(&reader, page_id), vec![1; PAGE_SIZE as usize]);
+
+        let later_reader = manager.begin();
+        assert_eq!(
+            manager.read_page(&later_reader, page_id),
+            vec![2; PAGE_SIZE as usize]
+        );
+
+        fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_abort_returns_allocated_page_to_free_list() {
+        let filename = "TXN_TEST_ABORT_FREES_PAGE";
+        let manager = temp_manager(filename);
+
+        let txn = manager.begin();
+        let page_id = txn.allocate_page(&manager.disk_manager);
+        manager.abort(txn);
+
+        assert_eq!(manager.disk_manager.is_allocated(page_id), false);
+
+        fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    /// Two transactions that COW from the same base version must not both succeed: the second
+    /// committer loses (first-committer-wins) instead of silently clobbering the first's write.
+    fn test_concurrent_writers_to_same_base_conflict_on_commit() {
+        let filename = "TXN_TEST_WRITE_WRITE_CONFLICT";
+        let manager = temp_manager(filename);
+
+        let setup_txn = manager.begin();
+        let page_id = setup_txn.allocate_page(&manager.disk_manager);
+        manager.write_page(&setup_txn, page_id, vec![0; PAGE_SIZE as usize]);
+        manager.commit(setup_txn).unwrap();
+
+        let a = manager.begin();
+        let b = manager.begin();
+
+        // Both transactions COW from the same base version (the one `setup_txn` published).
+        manager.write_page(&a, page_id, vec![10; PAGE_SIZE as usize]);
+        manager.write_page(&b, page_id, vec![20; PAGE_SIZE as usize]);
+
+        let a_id = a.id;
+        manager.commit(a).unwrap();
+
+        // `b`'s base version has since been superseded by `a`'s commit; `b` must be rejected
+        // rather than silently overwriting `a`'s write.
+        let conflict = manager.commit(b).unwrap_err();
+        assert_eq!(conflict, a_id);
+
+        let later_reader = manager.begin();
+        assert_eq!(
+            manager.read_page(&later_reader, page_id),
+            vec![10; PAGE_SIZE as usize]
+        );
+
+        fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    /// `vacuum` must not reclaim a version still needed by an active transaction's snapshot, even
+    /// if the transaction that superseded it has already committed and left the active set.
+    fn test_vacuum_respects_in_progress_set_of_still_active_snapshot() {
+        let filename = "TXN_TEST_VACUUM_HORIZON";
+        let manager = temp_manager(filename);
+
+        let setup_txn = manager.begin();
+        let page_id = setup_txn.allocate_page(&manager.disk_manager);
+        manager.write_page(&setup_txn, page_id, vec![1; PAGE_SIZE as usize]);
+        manager.commit(setup_txn).unwrap();
+
+        // `writer` begins first, then `reader` begins while `writer` is still active, so
+        // `writer`'s id ends up in `reader.snapshot.in_progress`. `writer` then commits and
+        // leaves the active set entirely -- at that point `active` only contains `reader`, whose
+        // *own* id is higher than `writer`'s. A horizon computed from just the min active id
+        // would therefore consider `writer`'s superseded version vacuumable, even though
+        // `reader` still can't see `writer` as committed and needs it.
+        let writer = manager.begin();
+        let reader = manager.begin();
+        manager.write_page(&writer, page_id, vec![2; PAGE_SIZE as usize]);
+        manager.commit(writer).unwrap();
+
+        manager.vacuum();
+
+        // The pre-`writer` version must have survived vacuum: `reader` still needs it.
+        assert_eq!(
+            manager.read_page(&reader, page_id),
+            vec![1; PAGE_SIZE as usize]
+        );
+
+        fs::remove_file(filename).unwrap();
+    }
+}
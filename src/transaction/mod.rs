@@ -0,0 +1,9 @@
+/*
+ * Copyright (c) 2021.  Shoyo Inokuchi.
+ * Please refer to github.com/shoyo/jindb for more information about this project and its license.
+ */
+
+pub mod manager;
+pub mod snapshot;
+pub mod version;
+pub mod version_store;
@@ -0,0 +1,46 @@
+/*
+ * Copyright (c) 2021.  Shoyo Inokuchi.
+ * Please refer to github.com/shoyo/jindb for more information about this project and its license.
+ */
+
+use crate::common::TransactionIdT;
+use crate::transaction::version::VersionStamp;
+use std::collections::HashSet;
+
+/// A snapshot of which transactions were committed at the moment a transaction began, used to
+/// decide which record versions that transaction is allowed to see. Mirrors the classic
+/// Postgres-style snapshot: a high-water mark plus the set of transactions still in progress
+/// below it, rather than an explicit list of every committed id.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    /// The id of the transaction this snapshot belongs to.
+    pub xid: TransactionIdT,
+
+    /// Transactions with an id at or above this mark started after this snapshot was taken, and
+    /// are therefore always invisible to it.
+    pub xid_high_water: TransactionIdT,
+
+    /// Transactions with an id below `xid_high_water` that were still in progress (neither
+    /// committed nor aborted) when this snapshot was taken, and are therefore invisible to it.
+    pub in_progress: HashSet<TransactionIdT>,
+}
+
+impl Snapshot {
+    /// Whether a record version stamped with `stamp` is visible to this snapshot: its creator
+    /// must be visible (committed-and-before the snapshot, or this snapshot's own transaction),
+    /// and its deleter, if any, must not be.
+    pub fn is_visible(&self, stamp: &VersionStamp) -> bool {
+        self.created_by_visible_txn(stamp.xmin) && !self.deleted_by_visible_txn(stamp.xmax)
+    }
+
+    fn created_by_visible_txn(&self, xmin: TransactionIdT) -> bool {
+        xmin == self.xid || (xmin < self.xid_high_water && !self.in_progress.contains(&xmin))
+    }
+
+    fn deleted_by_visible_txn(&self, xmax: Option<TransactionIdT>) -> bool {
+        match xmax {
+            None => false,
+            Some(xmax) => self.created_by_visible_txn(xmax),
+        }
+    }
+}
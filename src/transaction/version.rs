@@ -0,0 +1,30 @@
+/*
+ * Copyright (c) 2021.  Shoyo Inokuchi.
+ * Please refer to github.com/shoyo/jindb for more information about this project and its license.
+ */
+
+use crate::common::TransactionIdT;
+
+/// The hidden MVCC stamps carried by every record version: the transaction that created the
+/// version, and (once a later writer has superseded it) the transaction that deleted it. A
+/// record version is live only for readers whose snapshot can see `xmin` as committed but not
+/// `xmax`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionStamp {
+    pub xmin: TransactionIdT,
+    pub xmax: Option<TransactionIdT>,
+}
+
+impl VersionStamp {
+    /// Stamp a brand-new version created by `xmin`, with no deleting transaction yet.
+    pub fn created_by(xmin: TransactionIdT) -> Self {
+        Self { xmin, xmax: None }
+    }
+
+    /// Mark this version as superseded by `xmax`. Writers never overwrite a version in place;
+    /// superseding a version only ever sets `xmax` on the old one alongside appending a new one
+    /// stamped `created_by` the same transaction.
+    pub fn superseded_by(&mut self, xmax: TransactionIdT) {
+        self.xmax = Some(xmax);
+    }
+}
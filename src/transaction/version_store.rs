@@ -0,0 +1,120 @@
+/*
+ * Copyright (c) 2021.  Shoyo Inokuchi.
+ * Please refer to github.com/shoyo/jindb for more information about this project and its license.
+ */
+
+use crate::common::constants::PAGE_SIZE;
+use crate::common::{PageIdT, TransactionIdT};
+use crate::storage::disk_manager::DiskManager;
+use crate::transaction::snapshot::Snapshot;
+use crate::transaction::version::VersionStamp;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+struct PageVersion {
+    stamp: VersionStamp,
+    data: Vec<u8>,
+}
+
+/// The actual multi-version store backing MVCC: every page's version chain, oldest first. A
+/// reader never blocks a writer (or vice versa) because a writer only ever appends a new version
+/// and stamps the version it superseded with its own id as `xmax` — existing readers keep
+/// resolving against the versions their snapshot already pinned.
+pub struct VersionStore {
+    chains: Mutex<HashMap<PageIdT, Vec<PageVersion>>>,
+}
+
+impl VersionStore {
+    pub fn new() -> Self {
+        Self {
+            chains: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Resolve the version of `page_id` visible to `snapshot`, seeding the chain from the shared
+    /// disk manager (as an always-visible version created by transaction `0`) the first time the
+    /// page is touched through MVCC. Returns the visible version's `xmin` (so the caller can
+    /// later supersede exactly that version on commit) alongside its data.
+    pub fn visible_version(
+        &self,
+        disk_manager: &DiskManager,
+        page_id: PageIdT,
+        snapshot: &Snapshot,
+    ) -> (TransactionIdT, Vec<u8>) {
+        let mut chains = self.chains.lock().unwrap();
+        let chain = chains.entry(page_id).or_insert_with(|| {
+            let mut data = vec![0; PAGE_SIZE as usize];
+            disk_manager.read_page(page_id, &mut data);
+            vec![PageVersion {
+                stamp: VersionStamp::created_by(0),
+                data,
+            }]
+        });
+
+        chain
+            .iter()
+            .rev()
+            .find(|version| snapshot.is_visible(&version.stamp))
+            .map(|version| (version.stamp.xmin, version.data.clone()))
+            .expect("the base version (xmin = 0) is visible to every snapshot")
+    }
+
+    /// Publish every page `txn_id` wrote, written as `(page_id, base_xmin, data)` triples, as new
+    /// versions superseding the version each was copy-on-written from (or appending a brand-new
+    /// chain when `base_xmin` is `None`, meaning the page was freshly allocated by this
+    /// transaction and has no prior version).
+    ///
+    /// Validates every page before publishing any of them: if any page's `base_xmin` version has
+    /// already been superseded by another committed transaction, the whole batch is rejected and
+    /// nothing is published — classic first-committer-wins. Takes the store's lock for the whole
+    /// validate-then-apply sequence so two transactions racing to commit conflicting writes can't
+    /// both see the other's base version as not-yet-superseded and both succeed.
+    pub fn publish_all(
+        &self,
+        txn_id: TransactionIdT,
+        writes: Vec<(PageIdT, Option<TransactionIdT>, Vec<u8>)>,
+    ) -> Result<(), TransactionIdT> {
+        let mut chains = self.chains.lock().unwrap();
+
+        for (page_id, base_xmin, _) in &writes {
+            if let Some(base_xmin) = base_xmin {
+                if let Some(conflicting_xmax) = chains
+                    .get(page_id)
+                    .and_then(|chain| chain.iter().find(|v| v.stamp.xmin == *base_xmin))
+                    .and_then(|base| base.stamp.xmax)
+                {
+                    return Err(conflicting_xmax);
+                }
+            }
+        }
+
+        for (page_id, base_xmin, data) in writes {
+            let chain = chains.entry(page_id).or_insert_with(Vec::new);
+
+            if let Some(base_xmin) = base_xmin {
+                if let Some(base) = chain.iter_mut().find(|v| v.stamp.xmin == base_xmin) {
+                    base.stamp.superseded_by(txn_id);
+                }
+            }
+
+            chain.push(PageVersion {
+                stamp: VersionStamp::created_by(txn_id),
+                data,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Drop every version whose `xmax` is vacuumable, i.e. older than every still-live snapshot
+    /// and therefore impossible for any present or future reader to need.
+    pub fn vacuum(&self, is_vacuumable: impl Fn(TransactionIdT) -> bool) {
+        let mut chains = self.chains.lock().unwrap();
+        for chain in chains.values_mut() {
+            chain.retain(|version| match version.stamp.xmax {
+                Some(xmax) => !is_vacuumable(xmax),
+                None => true,
+            });
+        }
+    }
+}
@@ -130,6 +130,83 @@ fn test_concurrent_read_access() {
     }
 }
 
+#[test]
+/// Assert that a deallocated page is handed back by the next allocation instead of growing the
+/// file further.
+fn test_disk_deallocation_reuses_freed_page() {
+    let ctx = setup(7);
+    let manager = &ctx.disk_manager;
+
+    let page_id = manager.allocate_page();
+    let next_id = manager.allocate_page();
+
+    manager.deallocate_page(page_id);
+    assert_eq!(manager.is_allocated(page_id), false);
+
+    let reused_id = manager.allocate_page();
+    assert_eq!(reused_id, page_id);
+    assert_eq!(manager.is_allocated(page_id), true);
+
+    // The free-list was empty again, so this allocation must extend past the highest page
+    // handed out so far rather than reusing `next_id`.
+    let grown_id = manager.allocate_page();
+    assert!(grown_id > next_id);
+}
+
+#[test]
+/// Assert that a page handed back from the free-list comes back zeroed, rather than retaining
+/// its old free-list "next" pointer or prior contents.
+fn test_disk_reused_page_is_zeroed() {
+    let ctx = setup(8);
+    let manager = &ctx.disk_manager;
+
+    let page_id = manager.allocate_page();
+    manager.write_page(page_id, &[255; PAGE_SIZE as usize]);
+
+    manager.deallocate_page(page_id);
+    let reused_id = manager.allocate_page();
+    assert_eq!(reused_id, page_id);
+
+    let mut data = [1; PAGE_SIZE as usize];
+    manager.read_page(reused_id, &mut data);
+    assert_eq!(data, [0; PAGE_SIZE as usize]);
+}
+
+#[test]
+/// Assert that allocation state (which pages are allocated, and the free-list) survives closing
+/// and reopening the same database file, since neither is kept anywhere but on disk.
+fn test_disk_state_survives_reopen() {
+    let filename = "DM_TEST_9";
+
+    {
+        let manager = DiskManager::new(filename);
+        let live_page = manager.allocate_page();
+        manager.write_page(live_page, &[42; PAGE_SIZE as usize]);
+
+        let freed_page = manager.allocate_page();
+        manager.deallocate_page(freed_page);
+
+        assert_eq!(manager.is_allocated(live_page), true);
+        assert_eq!(manager.is_allocated(freed_page), false);
+    }
+
+    let manager = DiskManager::new(filename);
+
+    // The page that was still allocated when the file was closed must still be allocated (and
+    // readable) after reopening, not reset to "free".
+    assert_eq!(manager.is_allocated(1), true);
+    let mut data = [0; PAGE_SIZE as usize];
+    manager.read_page(1, &mut data);
+    assert_eq!(data, [42; PAGE_SIZE as usize]);
+
+    // The page that was freed before closing must still be free, and handed back out instead of
+    // growing the file.
+    assert_eq!(manager.is_allocated(2), false);
+    assert_eq!(manager.allocate_page(), 2);
+
+    fs::remove_file(filename).unwrap();
+}
+
 #[test]
 /// Assert that multiple threads can allocate and write to different pages on disk
 /// simultaneously.